@@ -3,13 +3,79 @@
 //! This module contains the support for capturing a stack backtrace through
 //! the [`Backtrace`] type. Backtraces are helpful to attach to errors,
 //! containing information that can be used to get a chain of where an error
-//! was created.
+//! was created. [`provide_backtrace`] and [`request_backtrace`] let an error
+//! type carry a `Backtrace` and expose it through `core::error`'s generic
+//! member-access API.
+//!
+//! That API (`core::error::Request`, `provide_ref`, `request_ref`) is behind
+//! the nightly `error_generic_member_access` feature, which this crate's
+//! root enables since this module uses those items directly.
 
-use alloc::vec::Vec;
-use core::{ffi::c_void, fmt::Display};
+use alloc::{string::String, vec::Vec};
+use core::{
+    ffi::c_void,
+    fmt::Display,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 #[cfg(target_arch = "arm")]
 use crate::unwind::*;
+use crate::symbolicate;
+
+// The `armv7a-vex-v5` linker script places `.text` at this fixed address,
+// and VEXos loads a fully-reuploaded user program at that same address, so
+// the two normally coincide and there is no relocation to account for.
+// They can diverge for partial ("hot"/"cold") uploads, which install a
+// program directly after an already-resident library at whatever address
+// that happens to end at - hence still reading the *runtime* `.text` start
+// below and comparing it against the link-time constant, rather than
+// assuming either one.
+#[cfg(target_arch = "arm")]
+const LINK_BASE: usize = 0x0380_0000;
+
+// Provided by the linker script: the address the program's `.text` segment
+// was actually loaded at this run.
+#[cfg(target_arch = "arm")]
+extern "C" {
+    static __text_start: u8;
+}
+
+/// Returns the bias between where this program was linked to run
+/// ([`LINK_BASE`]) and where it actually ended up in memory this run.
+///
+/// This is `0` for a normal full upload (link address == load address), in
+/// which case [`Backtrace::frames_relative`] is a no-op. It's nonzero only
+/// when the program has been relocated away from its link address, e.g. by
+/// a partial/hot-linked upload.
+#[cfg(target_arch = "arm")]
+fn load_bias() -> usize {
+    (&raw const __text_start as usize).saturating_sub(LINK_BASE)
+}
+
+/// Whether [`Backtrace::capture`] should actually walk the stack.
+///
+/// Backtraces are disabled by default, since unwinding the stack is too
+/// expensive to do unconditionally on every panic or error. Call
+/// [`set_capture_enabled`] to turn capturing on (for example, from a user
+/// program's startup code), or use [`Backtrace::force_capture`] to capture
+/// a single backtrace regardless of this switch.
+static CAPTURE_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enables or disables [`Backtrace::capture`] globally.
+///
+/// When disabled, `Backtrace::capture` returns immediately without walking
+/// the stack, and the resulting backtrace has a [`status`](Backtrace::status)
+/// of [`BacktraceStatus::Disabled`]. [`Backtrace::force_capture`] ignores
+/// this switch entirely.
+pub fn set_capture_enabled(enabled: bool) {
+    CAPTURE_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether [`Backtrace::capture`] is currently enabled.
+#[must_use]
+pub fn capture_enabled() -> bool {
+    CAPTURE_ENABLED.load(Ordering::Relaxed)
+}
 
 /// A captured stack backtrace.
 ///
@@ -24,9 +90,15 @@ use crate::unwind::*;
 /// ## Symbolication
 ///
 /// The number stored in each frame is not particularly meaningful to humans on its own.
-/// Using a tool such as `llvm-symbolizer` or `addr2line`, it can be turned into
-/// a function name and line number to show what functions were being run at
-/// the time of the backtrace's capture.
+/// Call [`Backtrace::resolve`] to resolve frames to function names and
+/// file/line locations on-device, using the program's own embedded debug
+/// info. Alternatively, copy the addresses from the `Display` output,
+/// which also ends with a ready-to-run `llvm-symbolizer` command line,
+/// into a host machine. A fully reuploaded program runs at
+/// the same address it was linked for, so these addresses already line up
+/// with the static addresses such tools expect; [`frames_relative`](Self::frames_relative)
+/// only needs to adjust them when the program was relocated by a
+/// partial/hot-linked upload.
 ///
 /// ```terminal
 /// $ llvm-symbolizer -p -e ./target/armv7a-vex-v5/debug/program_name 0x380217b 0x380209b
@@ -38,24 +110,127 @@ use crate::unwind::*;
 /// ## Platform Support
 ///
 /// WebAssembly platforms are not supported.
+///
+/// ## Capture Performance
+///
+/// Unwinding the stack is relatively expensive, so [`Backtrace::capture`] is
+/// a no-op until [`set_capture_enabled(true)`](set_capture_enabled) has been
+/// called somewhere in the program (for example, at startup). Use
+/// [`Backtrace::force_capture`] to capture a backtrace unconditionally.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Backtrace {
-    /// The instruction pointers of each frame in the backtrace.
-    pub frames: Vec<*const c_void>,
+    /// Each frame in the backtrace.
+    pub frames: Vec<BacktraceFrame>,
+    status: BacktraceStatus,
+    resolved: bool,
+    load_base: Option<usize>,
+}
+
+/// A single frame of a [`Backtrace`].
+///
+/// A frame stores the raw instruction pointer captured during unwinding,
+/// along with whatever symbol information [`Backtrace::resolve`] has since
+/// attached to it. Most frames resolve to exactly one symbol, but a frame
+/// may expand to several [`BacktraceSymbol`]s when the compiler has inlined
+/// one or more function calls into it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BacktraceFrame {
+    /// The instruction pointer of this frame.
+    pub ip: *const c_void,
+    /// The symbols this frame resolves to, outermost function last.
+    ///
+    /// Empty until [`Backtrace::resolve`] is called, and may remain empty
+    /// afterwards if no debug info could be found for `ip`.
+    pub symbols: Vec<BacktraceSymbol>,
+}
+
+/// Symbol information for a single (possibly inlined) function call within a
+/// [`BacktraceFrame`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BacktraceSymbol {
+    /// The demangled name of the function, if known.
+    pub name: Option<String>,
+    /// The name of the source file the function is defined in, if known.
+    pub filename: Option<String>,
+    /// The line number within `filename`, if known.
+    pub lineno: Option<u32>,
+    /// The column number within `filename`, if known.
+    pub colno: Option<u32>,
+}
+
+/// The current status of a [`Backtrace`].
+///
+/// This is returned by [`Backtrace::status`] and describes why a backtrace
+/// does or doesn't contain any frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BacktraceStatus {
+    /// Capturing a backtrace is not supported on the current platform.
+    Unsupported,
+    /// Capturing backtraces has been disabled.
+    Disabled,
+    /// The stack was walked, but no frames were found.
+    Empty,
+    /// A backtrace has been captured and contains frames.
+    Captured,
 }
 
 impl Backtrace {
-    /// Captures a backtrace at the current point of execution.
+    /// Captures a backtrace at the current point of execution, if capturing
+    /// is enabled.
     ///
-    /// If a backtrace could not be captured, an empty backtrace is returned.
+    /// Backtrace capture is disabled by default, since unwinding the stack
+    /// is too expensive to pay for on every panic or error. If capturing has
+    /// not been turned on with [`set_capture_enabled`], this returns
+    /// immediately with a [`status`](Self::status) of
+    /// [`BacktraceStatus::Disabled`] without touching the unwinder. Use
+    /// [`Backtrace::force_capture`] to always walk the stack.
     #[inline(always)] // Inlining keeps this function from appearing in backtraces
     #[allow(clippy::missing_const_for_fn)]
     pub fn capture() -> Self {
+        // Unsupported platforms report `Unsupported` unconditionally; the
+        // enabled/disabled switch only applies where capturing is otherwise
+        // possible.
+        #[cfg(target_arch = "wasm32")]
+        return Self::force_capture();
+
+        #[cfg(target_arch = "arm")]
+        {
+            if !capture_enabled() {
+                return Self {
+                    frames: Vec::new(),
+                    status: BacktraceStatus::Disabled,
+                    resolved: false,
+                    load_base: Some(load_bias()),
+                };
+            }
+
+            Self::force_capture()
+        }
+    }
+
+    /// Captures a backtrace at the current point of execution, regardless of
+    /// whether capturing has been enabled with [`set_capture_enabled`].
+    ///
+    /// If a backtrace could not be captured, an empty backtrace is returned.
+    #[inline(always)] // Inlining keeps this function from appearing in backtraces
+    #[allow(clippy::missing_const_for_fn)]
+    pub fn force_capture() -> Self {
         #[cfg(target_arch = "arm")]
-        return Self::try_capture().unwrap_or(Self { frames: Vec::new() });
+        return Self::try_capture().unwrap_or(Self {
+            frames: Vec::new(),
+            status: BacktraceStatus::Empty,
+            resolved: false,
+            load_base: Some(load_bias()),
+        });
 
         #[cfg(target_arch = "wasm32")]
-        return Self { frames: Vec::new() };
+        return Self {
+            frames: Vec::new(),
+            status: BacktraceStatus::Unsupported,
+            resolved: false,
+            load_base: None,
+        };
     }
 
     /// Captures a backtrace at the current point of execution,
@@ -79,23 +254,195 @@ impl Backtrace {
                 instruction_pointer -= 1;
             }
 
-            frames.push(instruction_pointer as *const c_void);
+            frames.push(BacktraceFrame {
+                ip: instruction_pointer as *const c_void,
+                symbols: Vec::new(),
+            });
+        }
+
+        let status = if frames.is_empty() {
+            BacktraceStatus::Empty
+        } else {
+            BacktraceStatus::Captured
+        };
+
+        Ok(Self {
+            frames,
+            status,
+            resolved: false,
+            load_base: Some(load_bias()),
+        })
+    }
+
+    /// Returns the status of this backtrace.
+    ///
+    /// This can be used to tell apart the several reasons a backtrace might
+    /// contain no frames, such as running on an unsupported platform or
+    /// having backtrace capture disabled, from one that was genuinely
+    /// captured successfully.
+    #[must_use]
+    pub const fn status(&self) -> BacktraceStatus {
+        self.status
+    }
+
+    /// Resolves each frame in this backtrace to the symbol(s) it corresponds
+    /// to, using the DWARF debug info embedded in the running program.
+    ///
+    /// This is separate from capture so that the hot capture path stays
+    /// cheap and allocation-light; call this only once you actually need to
+    /// print or inspect a backtrace. Resolution is best-effort: frames for
+    /// which no debug info can be found are left with an empty
+    /// [`symbols`](BacktraceFrame::symbols) list rather than causing this
+    /// method to fail. Calling this more than once is a no-op.
+    pub fn resolve(&mut self) {
+        if self.resolved {
+            return;
         }
 
-        Ok(Self { frames })
+        symbolicate::resolve(&mut self.frames, self.load_base);
+        self.resolved = true;
+    }
+
+    /// Returns whether [`Backtrace::resolve`] has been called on this
+    /// backtrace.
+    #[must_use]
+    pub const fn is_resolved(&self) -> bool {
+        self.resolved
+    }
+
+    /// Returns the load bias of this backtrace: how far the program's
+    /// runtime `.text` address diverged from the fixed address it was
+    /// linked to run at.
+    ///
+    /// This is `Some(0)` for a normal full upload, where the program runs at
+    /// its link address and [`frames`](Self::frames) already holds
+    /// file-relative addresses. It's only nonzero when the program has been
+    /// relocated away from its link address, e.g. by a partial/hot-linked
+    /// upload. `None` on platforms with no notion of a load address
+    /// (currently just `wasm32`).
+    #[must_use]
+    pub const fn load_base(&self) -> Option<usize> {
+        self.load_base
+    }
+
+    /// Returns this backtrace's frames with their instruction pointers made
+    /// file-relative, by subtracting [`load_base`](Self::load_base).
+    ///
+    /// A program that was fully reuploaded runs at the same address it was
+    /// linked at, in which case this is a no-op and yields the same
+    /// addresses as [`frames`](Self::frames). It only differs when the
+    /// program has been relocated to a different address than it was
+    /// linked for (e.g. a partial/hot-linked upload), in which case these
+    /// are the addresses a host-side symbolizer such as
+    /// `llvm-symbolizer -e program.elf` expects, rather than the raw
+    /// runtime ones.
+    pub fn frames_relative(&self) -> impl Iterator<Item = *const c_void> + '_ {
+        let base = self.load_base.unwrap_or(0);
+        self.frames
+            .iter()
+            .map(move |frame| (frame.ip as usize).saturating_sub(base) as *const c_void)
     }
 }
 
 impl Display for Backtrace {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.status {
+            BacktraceStatus::Unsupported => {
+                return write!(
+                    f,
+                    "note: backtrace capture is not supported on this platform"
+                );
+            }
+            BacktraceStatus::Disabled => {
+                return write!(f, "note: backtrace capture is disabled for this run");
+            }
+            BacktraceStatus::Empty => {
+                return write!(
+                    f,
+                    "note: the stack was walked, but no frames were captured"
+                );
+            }
+            BacktraceStatus::Captured => {}
+        }
+
         writeln!(f, "stack backtrace:")?;
         for (i, frame) in self.frames.iter().enumerate() {
-            writeln!(f, "{i:>3}: {:?}", frame)?;
+            if frame.symbols.is_empty() {
+                writeln!(f, "{i:>3}: {:?}", frame.ip)?;
+                continue;
+            }
+
+            for symbol in &frame.symbols {
+                let name = symbol.name.as_deref().unwrap_or("<unknown>");
+                write!(f, "{i:>3}: {name}")?;
+                if let Some(filename) = &symbol.filename {
+                    write!(f, "\n             at {filename}")?;
+                    if let Some(lineno) = symbol.lineno {
+                        write!(f, ":{lineno}")?;
+                        if let Some(colno) = symbol.colno {
+                            write!(f, ":{colno}")?;
+                        }
+                    }
+                }
+                writeln!(f)?;
+            }
+        }
+
+        if self.resolved {
+            write!(f, "note: backtrace resolved on-device from embedded debug info.")?;
+        } else if let Some(bias) = self.load_base {
+            writeln!(f, "note: addresses above are runtime addresses; load bias was {bias:#x}")?;
+            write!(f, "note: run: llvm-symbolizer -p -e program.elf")?;
+            for ip in self.frames_relative() {
+                write!(f, " {ip:?}")?;
+            }
+        } else {
+            write!(
+                f,
+                "note: Use a symbolizer to convert stack frames to human-readable function names."
+            )?;
         }
-        write!(
-            f,
-            "note: Use a symbolizer to convert stack frames to human-readable function names."
-        )?;
         Ok(())
     }
 }
+
+/// Makes `backtrace` available through `request`, for use inside an
+/// implementation of [`core::error::Error::provide`].
+///
+/// This lets an error type carry a [`Backtrace`] as a field and expose it
+/// through the standard generic member-access API instead of each crate
+/// having to re-implement `provide` by hand:
+///
+/// ```ignore
+/// impl core::error::Error for MyError {
+///     fn provide<'a>(&'a self, request: &mut core::error::Request<'a>) {
+///         provide_backtrace(&self.backtrace, request);
+///     }
+/// }
+/// ```
+///
+/// `core::error::Request` and [`core::error::request_ref`] are gated behind
+/// the nightly `error_generic_member_access` feature, which this crate
+/// enables at its root so that `vexide-panic` itself builds; a downstream
+/// crate implementing `MyError::provide` still needs to enable the feature
+/// in its own crate root to write a `provide` method at all.
+pub fn provide_backtrace<'a>(backtrace: &'a Backtrace, request: &mut core::error::Request<'a>) {
+    request.provide_ref::<Backtrace>(backtrace);
+}
+
+/// Walks `err`'s source chain and returns the first [`Backtrace`] attached
+/// via [`provide_backtrace`], if any.
+///
+/// This is the read-side counterpart to `provide_backtrace`, letting
+/// error-reporting code (including the vexide panic/abort path) print a
+/// backtrace for a `dyn Error` without knowing its concrete type.
+pub fn request_backtrace<'a>(err: &'a (dyn core::error::Error + 'static)) -> Option<&'a Backtrace> {
+    let mut err = Some(err);
+    while let Some(current) = err {
+        if let Some(backtrace) = core::error::request_ref::<Backtrace>(current) {
+            return Some(backtrace);
+        }
+        err = current.source();
+    }
+    None
+}
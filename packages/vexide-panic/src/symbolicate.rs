@@ -0,0 +1,202 @@
+//! On-device DWARF symbolication for [`Backtrace`](crate::backtrace::Backtrace) frames.
+//!
+//! A captured frame only stores a raw instruction pointer, which isn't very
+//! useful without a symbolizer running on a host machine. This module
+//! resolves frames directly on the V5 brain instead, by reading the DWARF
+//! debug sections embedded in the running program's own image and building
+//! an [`addr2line::Context`] over them with `gimli` in `no_std`/`alloc` mode.
+//!
+//! The debug sections are only linked in (and only readable as linker
+//! symbols) on `target_arch = "arm"`; elsewhere [`resolve`] is a no-op.
+//!
+//! Pinned to `addr2line >= 0.22`, which is where `Context::find_frames`
+//! started returning a [`addr2line::LookupResult`] instead of a bare `Result`
+//! (to support split-DWARF, which supplies the missing pieces by loading a
+//! separate DWARF package on demand). This module has no filesystem to load
+//! a DWARF package from, so [`addr2line::LookupResult::skip_all_loads`] is used
+//! to resolve using only the embedded sections above.
+
+#[cfg(target_arch = "arm")]
+use alloc::string::String;
+
+#[cfg(target_arch = "arm")]
+use gimli::{EndianSlice, LittleEndian, SectionId};
+
+use crate::backtrace::BacktraceFrame;
+#[cfg(target_arch = "arm")]
+use crate::backtrace::BacktraceSymbol;
+
+#[cfg(target_arch = "arm")]
+type Reader = EndianSlice<'static, LittleEndian>;
+
+// Provided by the linker script: the bounds of each `.debug_*` section in
+// the program image, so they can be read without a filesystem.
+#[cfg(target_arch = "arm")]
+extern "C" {
+    static __debug_abbrev_start: u8;
+    static __debug_abbrev_end: u8;
+    static __debug_addr_start: u8;
+    static __debug_addr_end: u8;
+    static __debug_info_start: u8;
+    static __debug_info_end: u8;
+    static __debug_line_start: u8;
+    static __debug_line_end: u8;
+    static __debug_line_str_start: u8;
+    static __debug_line_str_end: u8;
+    static __debug_ranges_start: u8;
+    static __debug_ranges_end: u8;
+    static __debug_rnglists_start: u8;
+    static __debug_rnglists_end: u8;
+    static __debug_str_start: u8;
+    static __debug_str_end: u8;
+    static __debug_str_offsets_start: u8;
+    static __debug_str_offsets_end: u8;
+}
+
+#[cfg(target_arch = "arm")]
+unsafe fn section_bytes(start: *const u8, end: *const u8) -> &'static [u8] {
+    let start = start as usize;
+    let end = end as usize;
+    core::slice::from_raw_parts(start as *const u8, end.saturating_sub(start))
+}
+
+/// Looks up the bytes of a single DWARF section by name, if the linker
+/// script emitted bounds for it. Sections that aren't present (e.g. the
+/// program was built without debug info) resolve to an empty slice, which
+/// `gimli` treats the same as a missing section.
+#[cfg(target_arch = "arm")]
+fn load_section(id: SectionId) -> Reader {
+    let bytes = unsafe {
+        match id {
+            SectionId::DebugAbbrev => {
+                section_bytes(&raw const __debug_abbrev_start, &raw const __debug_abbrev_end)
+            }
+            SectionId::DebugAddr => {
+                section_bytes(&raw const __debug_addr_start, &raw const __debug_addr_end)
+            }
+            SectionId::DebugInfo => {
+                section_bytes(&raw const __debug_info_start, &raw const __debug_info_end)
+            }
+            SectionId::DebugLine => {
+                section_bytes(&raw const __debug_line_start, &raw const __debug_line_end)
+            }
+            SectionId::DebugLineStr => section_bytes(
+                &raw const __debug_line_str_start,
+                &raw const __debug_line_str_end,
+            ),
+            SectionId::DebugRanges => {
+                section_bytes(&raw const __debug_ranges_start, &raw const __debug_ranges_end)
+            }
+            SectionId::DebugRngLists => section_bytes(
+                &raw const __debug_rnglists_start,
+                &raw const __debug_rnglists_end,
+            ),
+            SectionId::DebugStr => {
+                section_bytes(&raw const __debug_str_start, &raw const __debug_str_end)
+            }
+            SectionId::DebugStrOffsets => section_bytes(
+                &raw const __debug_str_offsets_start,
+                &raw const __debug_str_offsets_end,
+            ),
+            _ => &[],
+        }
+    };
+
+    EndianSlice::new(bytes, LittleEndian)
+}
+
+#[cfg(target_arch = "arm")]
+fn context() -> Option<addr2line::Context<Reader>> {
+    let dwarf = gimli::Dwarf::load(|id| Ok::<_, ()>(load_section(id))).ok()?;
+    addr2line::Context::from_dwarf(dwarf).ok()
+}
+
+/// Normalizes a captured instruction pointer against `load_base`, the bias
+/// it was captured with, so it can be looked up against DWARF info that
+/// describes the program's link-time (unrelocated) layout.
+///
+/// Pulled out of [`resolve`] as a pure function so the normalization math
+/// can be tested without needing a real `addr2line::Context`.
+fn normalize_ip(ip: *const core::ffi::c_void, load_base: Option<usize>) -> u64 {
+    (ip as usize).saturating_sub(load_base.unwrap_or(0)) as u64
+}
+
+/// Resolves symbol information for each frame, in place.
+///
+/// `load_base` is the bias the frames' instruction pointers were captured
+/// with (see [`Backtrace::load_base`](crate::backtrace::Backtrace::load_base)):
+/// since the DWARF info describes the program's static, unrelocated layout,
+/// each IP is normalized by subtracting it before being looked up.
+///
+/// This is best-effort: a frame whose (normalized) instruction pointer
+/// doesn't fall within any compilation unit covered by the embedded debug
+/// info (or if the program has no debug info at all) is simply left with an
+/// empty `symbols` list. A no-op on platforms without embedded debug
+/// sections.
+pub(crate) fn resolve(frames: &mut [BacktraceFrame], load_base: Option<usize>) {
+    #[cfg(target_arch = "arm")]
+    {
+        let Some(context) = context() else {
+            return;
+        };
+
+        for frame in frames {
+            let ip = normalize_ip(frame.ip, load_base);
+
+            let Ok(mut inline_frames) = context.find_frames(ip).skip_all_loads() else {
+                continue;
+            };
+
+            while let Ok(Some(inline_frame)) = inline_frames.next() {
+                let name = inline_frame
+                    .function
+                    .as_ref()
+                    .and_then(|function| function.demangle().ok())
+                    .map(|name| String::from(name.as_ref()));
+
+                let (filename, lineno, colno) = match inline_frame.location {
+                    Some(location) => (
+                        location.file.map(String::from),
+                        location.line,
+                        location.column,
+                    ),
+                    None => (None, None, None),
+                };
+
+                frame.symbols.push(BacktraceSymbol {
+                    name,
+                    filename,
+                    lineno,
+                    colno,
+                });
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "arm"))]
+    {
+        let _ = (frames, load_base);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize_ip;
+
+    #[test]
+    fn normalizes_a_relocated_ip_against_a_known_load_base() {
+        let link_address = 0x0380_217b;
+        let bias = 0x1000;
+        let runtime_ip = (link_address + bias) as *const core::ffi::c_void;
+
+        assert_eq!(normalize_ip(runtime_ip, Some(bias)), link_address as u64);
+    }
+
+    #[test]
+    fn is_a_no_op_for_a_full_upload_with_zero_bias() {
+        let ip = 0x0380_217b as *const core::ffi::c_void;
+
+        assert_eq!(normalize_ip(ip, Some(0)), ip as u64);
+        assert_eq!(normalize_ip(ip, None), ip as u64);
+    }
+}